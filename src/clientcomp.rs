@@ -0,0 +1,320 @@
+extern crate itertools;
+
+use self::itertools::free::join;
+use std::collections::HashMap;
+use std::str;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use websocket;
+use websocket::Receiver;
+use websocket::Sender;
+use websocket::WebSocketStream;
+use websocket::dataframe::{DataFrame, Opcode};
+use websocket::result::{WebSocketResult, WebSocketError};
+
+use comp;
+use error::SlError;
+use protocol::{self, ClientMessage, ServerMessage};
+
+pub type WSSend = websocket::client::Sender<WebSocketStream>;
+pub type WSReceive = websocket::client::Receiver<WebSocketStream>;
+pub type WSGame = comp::CompGame<WSSend>;
+
+/// How often we ping each connected player to check that they are still
+/// there.
+const PING_INTERVAL_MILLIS: u64 = 15000;
+
+/// A player who has not ponged back in this many ping intervals is treated
+/// as disconnected.
+const MAX_MISSED_PINGS: u32 = 3;
+
+fn send_msg(send: &mut WSSend, msg: ServerMessage) -> WebSocketResult<()> {
+    let frame = DataFrame::new(true, Opcode::Text, msg.to_wire().into_bytes());
+    return send.send_dataframe(&frame);
+}
+
+/// Sends a message to everyone in the game
+fn announce_msg(game: &mut WSGame, msg: ServerMessage, except: Option<usize>) {
+    let frame = DataFrame::new(true, Opcode::Text, msg.to_wire().into_bytes());
+
+    for mut send in game.players.iter_mut().enumerate().filter_map(|(n, p)| {
+                if Some(n) != except {
+                    p.send.as_mut()
+                } else {
+                    None
+                }
+            }) {
+        send.send_dataframe(&frame);
+    }
+}
+
+/// Waits for the user to provide all the necessary game information over the
+/// websocket, then returns the CompGame.  On failure, hands the `Sender`
+/// back alongside the error (mirroring `JoinResult::Taken`) so the caller
+/// can still report the error to the client before closing.
+pub fn host_comp(send: WSSend, receive: &mut WSReceive, name: String) -> Result<WSGame, (WSSend, SlError)> {
+    let msg = match receive.recv_dataframe() {
+        Ok(msg) => msg,
+        Err(e) => return Err((send, SlError::from(e))),
+    };
+    let msg = match String::from_utf8(msg.data) {
+        Ok(msg) => msg,
+        Err(e) => return Err((send, SlError::from(e))),
+    };
+
+    let words = match protocol::parse_wordlist(&msg) {
+        Ok(ClientMessage::WordList(words)) => {
+            let mut map = HashMap::new();
+            for (word, _already_guessed) in words {
+                map.insert(word, false);
+            }
+            map
+        },
+        _ => return Err((send, SlError::InvalidWord)),
+    };
+
+    // create the player
+    let p = comp::CompPlayer::new(name, send);
+
+    Ok(comp::CompGame::new(p, words))
+}
+
+/// Sends the name of the game to the host
+pub fn send_gamename(gamename: String, game: &Mutex<WSGame>) {
+    let g = &mut game.lock().unwrap();
+    let send = g.players[0].send.as_mut().unwrap();
+    let frame = DataFrame::new(true, Opcode::Text, gamename.into_bytes());
+    send.send_dataframe(&frame);
+}
+
+/// Gets called whenever a new player joins, on this player's thread.  This
+/// function should send the new player the current game state, and announce
+/// to everyone else that this player has joined.
+/// Will return an error if and only if sending a message to the new player
+/// fails.
+fn on_playerjoin(player_num: usize, game: &mut WSGame) -> Result<(), WebSocketError> {
+    let ref pname = game.players[player_num].name.clone();
+
+    // announce to everyone else that this player has joined
+    announce_msg(game, ServerMessage::Join(pname.clone()), Some(player_num));
+
+    let mut msgs = Vec::with_capacity(2);
+
+    // the player list
+    msgs.push(join(game.players.iter().map(|p| {
+        let mut s = p.name.to_owned();
+        if p.did_quit() {
+            s.push('_');
+        }
+        s
+    }), " "));
+
+    // the list of words
+    msgs.push(join(game.words_iter(), " "));
+
+    let mut send = game.players[player_num].send.as_mut().unwrap();
+
+    for m in msgs.into_iter() {
+        let frame = DataFrame::new(true, Opcode::Text, m.into_bytes());
+        try!(send.send_dataframe(&frame));
+    }
+
+    // current scores
+    for p in game.players.iter() {
+        try!(send_msg(game.players[player_num].send.as_mut().unwrap(),
+                      ServerMessage::Score(p.name.clone(), p.score)));
+    }
+
+    // players who have given up
+    for p in game.players.iter() {
+        if p.gaveup {
+            try!(send_msg(game.players[player_num].send.as_mut().unwrap(),
+                          ServerMessage::GiveUp(p.name.clone())));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn join_comp(mut send: WSSend, receive: &mut WSReceive, game: &Mutex<WSGame>) -> Result<usize, SlError> {
+    try!(send_msg(&mut send, ServerMessage::Ok));
+
+    // keep asking for a name until the user enters a valid one
+    for frame in receive.incoming_dataframes() {
+        let raw = try!(String::from_utf8(try!(frame).data));
+
+        let name = match protocol::parse_name(&raw) {
+            Ok(ClientMessage::SetName(name)) => name,
+            _ => {
+                try!(send_msg(&mut send, ServerMessage::BadName));
+                continue;
+            },
+        };
+
+        let ref mut game = *game.lock().unwrap();
+        match game.try_join(name, send) {
+            comp::JoinResult::Ok(n) => {
+                try!(on_playerjoin(n, game));
+                return Ok(n);
+            },
+            comp::JoinResult::Taken(mut s) => {
+                try!(send_msg(&mut s, ServerMessage::Taken));
+                send = s;
+            },
+        };
+    }
+
+    Err(SlError::Protocol)
+}
+
+/// Announces the winner (or, in the case of a tie, winners) of the game.
+fn on_gameover(game: &mut WSGame) {
+    for name in game.winners() {
+        announce_msg(game, ServerMessage::Winner(name.to_owned()), None);
+    }
+}
+
+pub fn game_loop(receive: &mut WSReceive, pnum: usize, game: &Mutex<WSGame>) -> Result<(), SlError> {
+    for frame in receive.incoming_dataframes() {
+        let frame = try!(frame);
+
+        match frame.opcode {
+            Opcode::Text => {
+                let msg = try!(String::from_utf8(frame.data));
+
+                let parsed = match protocol::parse(&msg) {
+                    Ok(parsed) => parsed,
+                    Err(_) => continue,
+                };
+
+                match parsed {
+                    ClientMessage::Attempt(word) => {
+                        let mut game = game.lock().unwrap();
+                        let success = game.attempt(pnum, word);
+                        if success {
+                            let name = game.players[pnum].name.clone();
+                            let score = game.players[pnum].score;
+                            announce_msg(&mut game, ServerMessage::Score(name, score), None);
+
+                            if game.all_guessed() {
+                                on_gameover(&mut game);
+                            }
+                        }
+                    },
+                    ClientMessage::GiveUp => {
+                        let mut game = game.lock().unwrap();
+                        let name = game.players[pnum].name.clone();
+                        announce_msg(&mut game, ServerMessage::GiveUp(name), Some(pnum));
+
+                        if game.player_giveup(pnum) {
+                            on_gameover(&mut game);
+                        }
+                    },
+                    ClientMessage::UnGiveUp => {
+                        let mut game = game.lock().unwrap();
+                        let name = game.players[pnum].name.clone();
+                        announce_msg(&mut game, ServerMessage::UnGiveUp(name), Some(pnum));
+
+                        game.player_ungiveup(pnum);
+                    },
+                    ClientMessage::Chat(chatmsg) => {
+                        let mut game = game.lock().unwrap();
+                        let name = game.players[pnum].name.clone();
+                        announce_msg(&mut game, ServerMessage::Chat(name, chatmsg), Some(pnum));
+                    },
+                    ClientMessage::SetName(_) | ClientMessage::WordList(_) => {
+                        // Not valid once the game has started; ignore.
+                    },
+                    ClientMessage::CallVoteKick(_) | ClientMessage::CallVoteReveal(_) |
+                    ClientMessage::Vote(_) => {
+                        // Competitive games have no voting; ignore.
+                    },
+                }
+            },
+            Opcode::Ping => {
+                let mut game = game.lock().unwrap();
+                if let Some(send) = game.players[pnum].send.as_mut() {
+                    let pong = DataFrame::new(true, Opcode::Pong, frame.data);
+                    try!(send.send_dataframe(&pong));
+                }
+            },
+            Opcode::Pong => {
+                let mut game = game.lock().unwrap();
+                game.players[pnum].last_pong = Instant::now();
+            },
+            Opcode::Close => {
+                break;
+            },
+            Opcode::Binary | Opcode::Continuation => {},
+        }
+    }
+    Ok(())
+}
+
+/// Periodically pings this player's connection and watches for missed pongs.
+/// If `MAX_MISSED_PINGS` ping intervals pass without a pong, the player is
+/// disconnected through the same path as a closed connection, so that the
+/// game does not wait on a half-open socket.
+///
+/// `gen` is the player's generation at the time this heartbeat was spawned;
+/// if a reconnect has since bumped it, this heartbeat is for a replaced
+/// connection and should stop instead of pinging the new one too.
+pub fn heartbeat(pnum: usize, gen: u64, game: &Mutex<WSGame>) {
+    loop {
+        thread::sleep(Duration::from_millis(PING_INTERVAL_MILLIS));
+
+        let missed = {
+            let mut locked = game.lock().unwrap();
+            if locked.players[pnum].gen != gen || locked.players[pnum].did_quit() {
+                return;
+            }
+
+            let timeout = Duration::from_millis(PING_INTERVAL_MILLIS * MAX_MISSED_PINGS as u64);
+            if locked.players[pnum].last_pong.elapsed() > timeout {
+                true
+            } else {
+                if let Some(send) = locked.players[pnum].send.as_mut() {
+                    let ping = DataFrame::new(true, Opcode::Ping, Vec::new());
+                    send.send_dataframe(&ping);
+                }
+                false
+            }
+        };
+
+        if missed {
+            on_disconnect(pnum, game);
+        }
+    }
+}
+
+/// Will be run when a player leaves the game, whether because their
+/// connection closed normally or because the heartbeat declared them dead.
+/// This function will set the player's status to quit, and will inform
+/// everyone else that the player has quit.  Additionally, if this quit
+/// triggers a game over, it will announce the winner(s).
+///
+/// Returns `None` if the player had already been disconnected (e.g. the
+/// heartbeat got there first), otherwise `Some(true)` if no one is left in
+/// the game.
+pub fn on_disconnect(pnum: usize, game: &Mutex<WSGame>) -> Option<bool> {
+    let mut game = game.lock().unwrap();
+    if game.players[pnum].did_quit() {
+        return None;
+    }
+
+    let name = game.players[pnum].name.clone();
+    announce_msg(&mut game, ServerMessage::Quit(name), Some(pnum));
+
+    match game.player_quit(pnum) {
+        Some(comp::QuitResult::AllGiveup) => {
+            on_gameover(&mut game);
+            Some(false)
+        },
+        Some(comp::QuitResult::AllQuit) => {
+            println!("everyone left!");
+            Some(true)
+        },
+        _ => Some(false)
+    }
+}
@@ -1,12 +1,11 @@
 extern crate itertools;
 
-//use std::string::FromUtf8Error;
-use std::error::Error;
 use self::itertools::free::join;
-use regex::Regex;
 use std::collections::HashMap;
 use std::str;
 use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 use websocket;
 use websocket::Receiver;
 use websocket::Sender;
@@ -15,19 +14,32 @@ use websocket::dataframe::{DataFrame, Opcode};
 use websocket::result::{WebSocketResult, WebSocketError};
 
 use coop;
+use error::SlError;
+use protocol::{self, ClientMessage, ServerMessage};
 
 pub type WSSend = websocket::client::Sender<WebSocketStream>;
 pub type WSReceive = websocket::client::Receiver<WebSocketStream>;
 pub type WSGame = coop::CoopGame<WSSend>;
 
-fn send_msg(send: &mut WSSend, msg: String) -> WebSocketResult<()> {
-    let msg = DataFrame::new(true, Opcode::Text, msg.into_bytes());
-    return send.send_dataframe(&msg);
+/// How often we ping each connected player to check that they are still
+/// there.
+const PING_INTERVAL_MILLIS: u64 = 15000;
+
+/// A player who has not ponged back in this many ping intervals is treated
+/// as disconnected.
+const MAX_MISSED_PINGS: u32 = 3;
+
+/// How long a `:callvote` may stay open before it is silently dropped.
+const VOTE_TIMEOUT_MILLIS: u64 = 60000;
+
+fn send_msg(send: &mut WSSend, msg: ServerMessage) -> WebSocketResult<()> {
+    let frame = DataFrame::new(true, Opcode::Text, msg.to_wire().into_bytes());
+    return send.send_dataframe(&frame);
 }
 
 /// Sends a message to everyone in the game
-fn announce_msg(game: &mut WSGame, msg: String, except: Option<usize>) {
-    let msg = DataFrame::new(true, Opcode::Text, msg.into_bytes());
+fn announce_msg(game: &mut WSGame, msg: ServerMessage, except: Option<usize>) {
+    let frame = DataFrame::new(true, Opcode::Text, msg.to_wire().into_bytes());
 
     for mut send in game.players.iter_mut().enumerate().filter_map(|(n, p)| {
                 if Some(n) != except {
@@ -36,49 +48,52 @@ fn announce_msg(game: &mut WSGame, msg: String, except: Option<usize>) {
                     None
                 }
             }) {
-        send.send_dataframe(&msg);
+        send.send_dataframe(&frame);
     }
 }
 
 /// Waits for the user to provide all the necessary game information over the
-/// websocket, then returns the CoopGame.
-/// Will panic if anything goes wrong.
-pub fn host_coop(send: WSSend, receive: &mut WSReceive, name: String) -> WSGame {
-    lazy_static! {
-        static ref WORDREGEX: Regex = Regex::new(r"[a-z]{3,6}_?").unwrap();
-    }
-
-    let mut words = HashMap::new();
-
-    let msg = receive.recv_dataframe().unwrap();
-
-    // get the list of words from the client
-    for word in msg.data.split(|c| c == &(' ' as u8)).take(75) {
-        let word = str::from_utf8(word).unwrap();
-        if WORDREGEX.is_match(word) {
-            // If a word has already been guessed, it will end with a _
-            if word.bytes().rev().next() == Some('_' as u8) {
-                words.insert(word[..word.len()-1].to_owned(),
-                                  coop::Guesser::Player(0));
-            } else {
-                words.insert(word.to_owned(), coop::Guesser::NoOne);
+/// websocket, then returns the CoopGame.  On failure, hands the `Sender`
+/// back alongside the error (mirroring `JoinResult::Taken`) so the caller
+/// can still report the error to the client before closing.
+pub fn host_coop(send: WSSend, receive: &mut WSReceive, name: String) -> Result<WSGame, (WSSend, SlError)> {
+    let msg = match receive.recv_dataframe() {
+        Ok(msg) => msg,
+        Err(e) => return Err((send, SlError::from(e))),
+    };
+    let msg = match String::from_utf8(msg.data) {
+        Ok(msg) => msg,
+        Err(e) => return Err((send, SlError::from(e))),
+    };
+
+    let words = match protocol::parse_wordlist(&msg) {
+        Ok(ClientMessage::WordList(words)) => {
+            let mut map = HashMap::new();
+            for (word, already_guessed) in words {
+                let guesser = if already_guessed {
+                    coop::Guesser::Player(0)
+                } else {
+                    coop::Guesser::NoOne
+                };
+                map.insert(word, guesser);
             }
-        } else {
-            panic!();
-        }
-    }
+            map
+        },
+        _ => return Err((send, SlError::InvalidWord)),
+    };
 
     // create the player
     let p = coop::CoopPlayer::new(name, send);
 
-    coop::CoopGame::new(p, words)
+    Ok(coop::CoopGame::new(p, words))
 }
 
 /// Sends the name of the game to the host
 pub fn send_gamename(gamename: String, game: &Mutex<WSGame>) {
     let g = &mut game.lock().unwrap();
     let send = g.players[0].send.as_mut().unwrap();
-    send_msg(send, gamename);
+    let frame = DataFrame::new(true, Opcode::Text, gamename.into_bytes());
+    send.send_dataframe(&frame);
 }
 
 
@@ -89,9 +104,9 @@ pub fn send_gamename(gamename: String, game: &Mutex<WSGame>) {
 /// fails.
 fn on_playerjoin(player_num: usize, game: &mut WSGame) -> Result<(), WebSocketError> {
     let ref pname = game.players[player_num].name.clone();
-    
+
     // announce to everyone else that this player has joined
-    announce_msg(game, format!(":join {}", pname), Some(player_num));
+    announce_msg(game, ServerMessage::Join(pname.clone()), Some(player_num));
 
     let mut msgs = Vec::with_capacity(2);
 
@@ -107,51 +122,51 @@ fn on_playerjoin(player_num: usize, game: &mut WSGame) -> Result<(), WebSocketEr
     // the list of words
     msgs.push(join(game.words_iter(), " "));
 
+    let mut send = game.players[player_num].send.as_mut().unwrap();
+
+    for m in msgs.into_iter() {
+        let frame = DataFrame::new(true, Opcode::Text, m.into_bytes());
+        try!(send.send_dataframe(&frame));
+    }
+
     // previously guessed words
-    msgs.extend(game.words.iter().filter_map(|(k, v)| {
-        if let coop::Guesser::Player(n) = *v {
-            Some(format!(":attempt {} {}",
-                         k,
-                         game.players[n].name))
-        } else if let coop::Guesser::Gaveup = *v {
-            Some(format!(":attempt {} _", k))
-        } else {
-            None
+    for (k, v) in game.words.iter() {
+        let msg = match *v {
+            coop::Guesser::Player(n) => Some(ServerMessage::Attempt(k.clone(), game.players[n].name.clone())),
+            coop::Guesser::Gaveup => Some(ServerMessage::AttemptGaveUp(k.clone())),
+            coop::Guesser::NoOne => None,
+        };
+        if let Some(msg) = msg {
+            try!(send_msg(game.players[player_num].send.as_mut().unwrap(), msg));
         }
-    }));
+    }
 
     // players who have given up
-    msgs.extend(game.players.iter().filter_map(|p| {
+    for p in game.players.iter() {
         if p.gaveup {
-            Some(format!(":giveup {}", p.name))
-        } else {
-            None
+            try!(send_msg(game.players[player_num].send.as_mut().unwrap(),
+                          ServerMessage::GiveUp(p.name.clone())));
         }
-    }));
-
-    let mut send = game.players[player_num].send.as_mut().unwrap();
-
-    for m in msgs.into_iter() {
-        try!(send_msg(send, m));
     }
 
     Ok(())
 }
 
-pub fn join_coop(mut send: WSSend, receive: &mut WSReceive, game: &Mutex<WSGame>) -> Result<usize, WebSocketError> {
-    lazy_static! {
-        static ref NAMEREGEX: Regex = Regex::new(r"^[a-zA-Z0-9]{1,10}$").unwrap();
-    }
-    send_msg(&mut send, String::from(":ok"));
+pub fn join_coop(mut send: WSSend, receive: &mut WSReceive, game: &Mutex<WSGame>) -> Result<usize, SlError> {
+    try!(send_msg(&mut send, ServerMessage::Ok));
 
     // keep asking for a name until the user enters a valid one
     for frame in receive.incoming_dataframes() {
-        let name = String::from_utf8(frame.unwrap().data).unwrap();
+        let raw = try!(String::from_utf8(try!(frame).data));
+
+        let name = match protocol::parse_name(&raw) {
+            Ok(ClientMessage::SetName(name)) => name,
+            _ => {
+                try!(send_msg(&mut send, ServerMessage::BadName));
+                continue;
+            },
+        };
 
-        if !NAMEREGEX.is_match(&name) {
-            send_msg(&mut send, String::from(":badname")).unwrap();
-            continue;
-        }
         let ref mut game = *game.lock().unwrap();
         match game.try_join(name, send) {
             coop::JoinResult::Ok(n) => {
@@ -159,91 +174,205 @@ pub fn join_coop(mut send: WSSend, receive: &mut WSReceive, game: &Mutex<WSGame>
                 return Ok(n);
             },
             coop::JoinResult::Taken(mut s) => {
-                send_msg(&mut s, String::from(":taken")).unwrap();
+                try!(send_msg(&mut s, ServerMessage::Taken));
                 send = s;
             },
         };
     }
 
-    panic!();
+    Err(SlError::Protocol)
 }
 
 /// Sends the allgiveup message to everyone
 fn on_allgiveup(game: &mut WSGame) {
-    announce_msg(game, String::from(":allgiveup"), None);
+    announce_msg(game, ServerMessage::AllGiveUp, None);
 }
 
-pub fn game_loop(receive: &mut WSReceive, pnum: usize, game: &Mutex<WSGame>) -> Result<(), Box<Error>> {
-    lazy_static! {
-        static ref ATTEMPT: Regex = Regex::new(r"^:attempt ([a-z]{3,6})$").unwrap();
-    }
-
+pub fn game_loop(receive: &mut WSReceive, pnum: usize, game: &Mutex<WSGame>) -> Result<(), SlError> {
     for frame in receive.incoming_dataframes() {
-        let msg = try!(String::from_utf8(try!(frame).data));
-
-        if let Some(c) = ATTEMPT.captures(&msg) {
-            let word = &c[1];
-            let mut game = game.lock().unwrap();
-            let success = game.attempt(pnum, word.to_owned());
-            if success {
-                let announce = {
-                    let ref name = game.players[pnum].name;
-                    format!(":attempt {} {}", word, name)
+        let frame = try!(frame);
+
+        match frame.opcode {
+            Opcode::Text => {
+                let msg = try!(String::from_utf8(frame.data));
+
+                let parsed = match protocol::parse(&msg) {
+                    Ok(parsed) => parsed,
+                    Err(_) => continue,
                 };
-                announce_msg(&mut game, announce, Some(pnum));
+
+                match parsed {
+                    ClientMessage::Attempt(word) => {
+                        let mut game = game.lock().unwrap();
+                        let success = game.attempt(pnum, word.clone());
+                        if success {
+                            let name = game.players[pnum].name.clone();
+                            announce_msg(&mut game, ServerMessage::Attempt(word, name), Some(pnum));
+                        }
+                    },
+                    ClientMessage::GiveUp => {
+                        let mut game = game.lock().unwrap();
+                        let name = game.players[pnum].name.clone();
+                        announce_msg(&mut game, ServerMessage::GiveUp(name), Some(pnum));
+
+                        if game.player_giveup(pnum) {
+                            on_allgiveup(&mut game);
+                        }
+                    },
+                    ClientMessage::UnGiveUp => {
+                        let mut game = game.lock().unwrap();
+                        let name = game.players[pnum].name.clone();
+                        announce_msg(&mut game, ServerMessage::UnGiveUp(name), Some(pnum));
+
+                        game.player_ungiveup(pnum);
+                    },
+                    ClientMessage::Chat(chatmsg) => {
+                        let mut game = game.lock().unwrap();
+                        let name = game.players[pnum].name.clone();
+                        announce_msg(&mut game, ServerMessage::Chat(name, chatmsg), Some(pnum));
+                    },
+                    ClientMessage::CallVoteKick(name) => {
+                        let mut game = game.lock().unwrap();
+                        let target = game.players.iter()
+                            .position(|p| p.name == name && !p.did_quit());
+                        if let Some(target) = target {
+                            if game.start_vote(pnum, coop::VoteType::Kick(target)) {
+                                let total = game.players.iter().filter(|p| !p.did_quit()).count() as u32;
+                                announce_msg(&mut game, ServerMessage::VoteCount(1, total), None);
+                            }
+                        }
+                    },
+                    ClientMessage::CallVoteReveal(word) => {
+                        let mut game = game.lock().unwrap();
+                        if game.words.contains_key(&word) {
+                            if game.start_vote(pnum, coop::VoteType::Reveal(word)) {
+                                let total = game.players.iter().filter(|p| !p.did_quit()).count() as u32;
+                                announce_msg(&mut game, ServerMessage::VoteCount(1, total), None);
+                            }
+                        }
+                    },
+                    ClientMessage::Vote(yes) => {
+                        let mut game = game.lock().unwrap();
+                        if let Some(tally) = game.cast_vote(pnum, yes) {
+                            announce_msg(&mut game, ServerMessage::VoteCount(tally.yes, tally.total), None);
+
+                            if let Some(kind) = tally.passed {
+                                match kind {
+                                    coop::VoteType::Kick(target) => {
+                                        if !game.players[target].did_quit() {
+                                            let name = game.players[target].name.clone();
+                                            match game.player_quit(target) {
+                                                Some(coop::QuitResult::AllGiveup) => on_allgiveup(&mut game),
+                                                Some(coop::QuitResult::AllQuit) => println!("everyone left!"),
+                                                _ => {},
+                                            }
+                                            announce_msg(&mut game, ServerMessage::Kicked(name), None);
+                                        }
+                                    },
+                                    coop::VoteType::Reveal(word) => {
+                                        if game.words.get(&word) == Some(&coop::Guesser::NoOne) {
+                                            game.words.insert(word.clone(), coop::Guesser::Gaveup);
+                                            announce_msg(&mut game, ServerMessage::AttemptGaveUp(word), None);
+                                        }
+                                    },
+                                }
+                            }
+                        }
+                    },
+                    ClientMessage::SetName(_) | ClientMessage::WordList(_) => {
+                        // Not valid once the game has started; ignore.
+                    },
+                }
+            },
+            Opcode::Ping => {
+                let mut game = game.lock().unwrap();
+                if let Some(send) = game.players[pnum].send.as_mut() {
+                    let pong = DataFrame::new(true, Opcode::Pong, frame.data);
+                    try!(send.send_dataframe(&pong));
+                }
+            },
+            Opcode::Pong => {
+                let mut game = game.lock().unwrap();
+                game.players[pnum].last_pong = Instant::now();
+            },
+            Opcode::Close => {
+                break;
+            },
+            Opcode::Binary | Opcode::Continuation => {},
+        }
+    }
+    Ok(())
+}
+
+/// Periodically pings this player's connection and watches for missed pongs.
+/// If `MAX_MISSED_PINGS` ping intervals pass without a pong, the player is
+/// disconnected through the same path as a closed connection, so that the
+/// game does not wait on a half-open socket.
+///
+/// Each tick also expires a vote that has been open too long; any connected
+/// player's heartbeat can do this, it's harmless for more than one to try.
+///
+/// `gen` is the player's generation at the time this heartbeat was spawned;
+/// if a reconnect has since bumped it, this heartbeat is for a replaced
+/// connection and should stop instead of pinging the new one too.
+pub fn heartbeat(pnum: usize, gen: u64, game: &Mutex<WSGame>) {
+    loop {
+        thread::sleep(Duration::from_millis(PING_INTERVAL_MILLIS));
+
+        let missed = {
+            let mut locked = game.lock().unwrap();
+            if locked.players[pnum].gen != gen || locked.players[pnum].did_quit() {
+                return;
             }
-        } else if msg == ":giveup" {
-            let mut game = game.lock().unwrap();
-            let announce = {
-                let ref name = game.players[pnum].name;
-                format!(":giveup {}", name)
-            };
-            announce_msg(&mut game, announce, Some(pnum));
-
-            if game.player_giveup(pnum) {
-                on_allgiveup(&mut game);
+
+            locked.expire_vote(Duration::from_millis(VOTE_TIMEOUT_MILLIS));
+
+            let timeout = Duration::from_millis(PING_INTERVAL_MILLIS * MAX_MISSED_PINGS as u64);
+            if locked.players[pnum].last_pong.elapsed() > timeout {
+                true
+            } else {
+                if let Some(send) = locked.players[pnum].send.as_mut() {
+                    let ping = DataFrame::new(true, Opcode::Ping, Vec::new());
+                    send.send_dataframe(&ping);
+                }
+                false
             }
-        } else if msg == ":ungiveup" {
-            let mut game = game.lock().unwrap();
-            let announce = {
-                let ref name = game.players[pnum].name;
-                format!(":ungiveup {}", name)
-            };
-            announce_msg(&mut game, announce, Some(pnum));
-
-            game.player_ungiveup(pnum);
-        } else if msg.starts_with(":chat ") {
-            let mut game = game.lock().unwrap();
-            let chatmsg = &msg[":chat ".len() ..];
-            let announce = {
-                let ref name = game.players[pnum].name;
-                format!(":chat {} {}", name, chatmsg)
-            };
-            announce_msg(&mut game, announce, Some(pnum));
+        };
+
+        if missed {
+            on_disconnect(pnum, game);
         }
     }
-    Ok(())
 }
 
-/// Will be run when a player leaves the game.  This function will set the
-/// player's status to quit, and will inform everyone else that the player has
-/// quit.  Additionally, if this quit triggers an allgiveup, it will modify the
-/// guessed players to reflect this, and send the allgiveup message.  Will
-/// return true if no one is left in the game.
-pub fn on_disconnect(pnum: usize, game: &Mutex<WSGame>) -> bool {
+/// Will be run when a player leaves the game, whether because their
+/// connection closed normally or because the heartbeat declared them dead.
+/// This function will set the player's status to quit, and will inform
+/// everyone else that the player has quit.  Additionally, if this quit
+/// triggers an allgiveup, it will modify the guessed players to reflect this,
+/// and send the allgiveup message.
+///
+/// Returns `None` if the player had already been disconnected (e.g. the
+/// heartbeat got there first), otherwise `Some(true)` if no one is left in
+/// the game.
+pub fn on_disconnect(pnum: usize, game: &Mutex<WSGame>) -> Option<bool> {
     let mut game = game.lock().unwrap();
-    let msg = format!(":quit {}", game.players[pnum].name);
-    announce_msg(&mut game, msg, Some(pnum));
+    if game.players[pnum].did_quit() {
+        return None;
+    }
+
+    let name = game.players[pnum].name.clone();
+    announce_msg(&mut game, ServerMessage::Quit(name), Some(pnum));
 
     match game.player_quit(pnum) {
         Some(coop::QuitResult::AllGiveup) => {
             on_allgiveup(&mut game);
-            false
+            Some(false)
         },
         Some(coop::QuitResult::AllQuit) => {
             println!("everyone left!");
-            true
+            Some(true)
         },
-        _ => false
+        _ => Some(false)
     }
 }
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::collections::hash_map::{Entry, Keys};
+use std::time::Instant;
+
+pub struct CompPlayer<T> {
+    pub name: String,
+    pub send: Option<T>,
+    pub gaveup: bool,
+    pub score: u32,
+
+    /// The last time we heard a pong from this player, used by the heartbeat
+    /// to detect dead connections.
+    pub last_pong: Instant,
+
+    /// Bumped every time this player slot is reused by a reconnect, so the
+    /// heartbeat thread spawned for a since-replaced connection can notice
+    /// it is stale and stop instead of pinging the new connection too.
+    pub gen: u64,
+}
+
+impl<T> CompPlayer<T> {
+    pub fn new(name: String, send: T) -> CompPlayer<T> {
+        CompPlayer {
+            name: name,
+            send: Some(send),
+            gaveup: false,
+            score: 0,
+            last_pong: Instant::now(),
+            gen: 0,
+        }
+    }
+
+    pub fn did_quit(&self) -> bool {
+        return self.send.is_none();
+    }
+}
+
+pub struct CompGame<T> {
+    pub players: Vec<CompPlayer<T>>,
+    pub words: HashMap<String, bool>,
+}
+
+pub enum JoinResult<T> {
+    Ok(usize),
+    Taken(T),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum QuitResult {
+    AllGiveup,
+    AllQuit,
+}
+
+impl<T> CompGame<T> {
+    pub fn new(first_player: CompPlayer<T>, words: HashMap<String, bool>) -> CompGame<T> {
+        CompGame {
+            players: vec![first_player],
+            words: words,
+        }
+    }
+
+    /// Returns a String iterator that iterates through the list of words.
+    pub fn words_iter(&self) -> Keys<String, bool> {
+        return self.words.keys();
+    }
+
+    pub fn try_join(&mut self, name: String, send: T) -> JoinResult<T> {
+        // First see if the player already exists.  If the player exists
+        // but previously left the game, replace them.
+        for (n, p) in self.players.iter_mut().enumerate() {
+            if p.name == name {
+                if p.did_quit() {
+                    p.send = Some(send);
+                    p.last_pong = Instant::now();
+                    p.gen = p.gen.wrapping_add(1);
+                    return JoinResult::Ok(n);
+                } else {
+                    return JoinResult::Taken(send);
+                }
+            }
+        }
+
+        let new_p = CompPlayer::new(name, send);
+        self.players.push(new_p);
+        return JoinResult::Ok(self.players.len() - 1);
+    }
+
+    /// Attempt to guess a word.  Returns true if the guess was successful (the
+    /// word existed and had not yet been locked), in which case the guessing
+    /// player's score has been incremented and the word is now locked.
+    pub fn attempt(&mut self, player_num: usize, word: String) -> bool {
+        if let Entry::Occupied(mut e) = self.words.entry(word) {
+            if !*e.get() {
+                e.insert(true);
+                self.players[player_num].score += 1;
+                return true;
+            }
+        }
+        return false;
+    }
+
+    /// Returns true once every word has been guessed.
+    pub fn all_guessed(&self) -> bool {
+        return self.words.values().all(|guessed| *guessed);
+    }
+
+    /// Returns the names of the player(s) with the highest score.  If more
+    /// than one player is tied for the highest score, all of them are
+    /// returned.
+    pub fn winners(&self) -> Vec<&str> {
+        let top = self.players.iter().map(|p| p.score).max().unwrap_or(0);
+        return self.players.iter()
+            .filter(|p| p.score == top)
+            .map(|p| p.name.as_str())
+            .collect();
+    }
+
+    /// Removes this player's send entry, and sets its giveup status to false.
+    /// If this player quitting will trigger an AllGiveup or AllQuit, this
+    /// function will return Some(QuitResult); otherwise will return None.
+    pub fn player_quit(&mut self, player_num: usize) -> Option<QuitResult> {
+        self.players[player_num].send = None;
+        self.players[player_num].gaveup = false;
+
+        let mut result = QuitResult::AllQuit;
+        for p in self.players.iter() {
+            if !p.did_quit() {
+                if !p.gaveup {
+                    return None;
+                }
+                result = QuitResult::AllGiveup;
+            }
+        }
+
+        return Some(result);
+    }
+
+    /// Sets a player's giveup status to true.  If this will trigger an
+    /// allgiveup, returns true.
+    pub fn player_giveup(&mut self, player_num: usize) -> bool {
+        self.players[player_num].gaveup = true;
+
+        return self.players.iter().all(|p| p.gaveup || p.did_quit());
+    }
+
+    pub fn player_ungiveup(&mut self, player_num: usize) {
+        self.players[player_num].gaveup = false;
+    }
+}
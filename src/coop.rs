@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use std::collections::hash_map::{Entry, Keys};
+use std::time::{Duration, Instant};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Guesser {
@@ -12,6 +13,15 @@ pub struct CoopPlayer<T> {
     pub name: String,
     pub send: Option<T>,
     pub gaveup: bool,
+
+    /// The last time we heard a pong from this player, used by the heartbeat
+    /// to detect dead connections.
+    pub last_pong: Instant,
+
+    /// Bumped every time this player slot is reused by a reconnect, so the
+    /// heartbeat thread spawned for a since-replaced connection can notice
+    /// it is stale and stop instead of pinging the new connection too.
+    pub gen: u64,
 }
 
 impl<T> CoopPlayer<T> {
@@ -20,6 +30,8 @@ impl<T> CoopPlayer<T> {
             name: name,
             send: Some(send),
             gaveup: false,
+            last_pong: Instant::now(),
+            gen: 0,
         }
     }
 
@@ -28,9 +40,37 @@ impl<T> CoopPlayer<T> {
     }
 }
 
+/// What a `Vote` is trying to accomplish.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VoteType {
+    /// Forcibly disconnect the player at this index.
+    Kick(usize),
+
+    /// Reveal this word, as if everyone had given up.
+    Reveal(String),
+}
+
+/// An in-progress vote.  At most one of these is active for a game at a
+/// time.
+pub struct Vote {
+    pub kind: VoteType,
+    pub votes: HashMap<usize, bool>,
+    pub initiator: usize,
+    pub started: Instant,
+}
+
+/// The result of casting a ballot: how the tally stands, and whether the
+/// vote has now passed.
+pub struct VoteTally {
+    pub yes: u32,
+    pub total: u32,
+    pub passed: Option<VoteType>,
+}
+
 pub struct CoopGame<T> {
     pub players: Vec<CoopPlayer<T>>,
     pub words: HashMap<String, Guesser>,
+    pub vote: Option<Vote>,
 }
 
 pub enum JoinResult<T> {
@@ -49,9 +89,72 @@ impl<T> CoopGame<T> {
         CoopGame {
             players: vec![first_player],
             words: words,
+            vote: None,
         }
     }
 
+    /// The number of players who are still connected.
+    fn connected_count(&self) -> u32 {
+        return self.players.iter().filter(|p| !p.did_quit()).count() as u32;
+    }
+
+    /// Starts a new vote, with the initiator's ballot counted as an
+    /// automatic yes.  Returns false (and does nothing) if a vote is already
+    /// in progress.
+    pub fn start_vote(&mut self, initiator: usize, kind: VoteType) -> bool {
+        if self.vote.is_some() {
+            return false;
+        }
+
+        let mut votes = HashMap::new();
+        votes.insert(initiator, true);
+        self.vote = Some(Vote {
+            kind: kind,
+            votes: votes,
+            initiator: initiator,
+            started: Instant::now(),
+        });
+        return true;
+    }
+
+    /// Records a ballot from `player_num`.  Returns `None` if no vote is in
+    /// progress.  Otherwise returns the updated tally; once strictly more
+    /// than half of the connected players have voted yes, the vote is
+    /// cleared and `VoteTally::passed` is set to the action that should now
+    /// be carried out.
+    pub fn cast_vote(&mut self, player_num: usize, yes: bool) -> Option<VoteTally> {
+        if self.vote.is_none() {
+            return None;
+        }
+
+        self.vote.as_mut().unwrap().votes.insert(player_num, yes);
+
+        let total = self.connected_count();
+        let yes_count = {
+            let vote = self.vote.as_ref().unwrap();
+            self.players.iter().enumerate()
+                .filter(|&(n, p)| !p.did_quit() && *vote.votes.get(&n).unwrap_or(&false))
+                .count() as u32
+        };
+
+        if yes_count * 2 > total {
+            let vote = self.vote.take().unwrap();
+            Some(VoteTally { yes: yes_count, total: total, passed: Some(vote.kind) })
+        } else {
+            Some(VoteTally { yes: yes_count, total: total, passed: None })
+        }
+    }
+
+    /// Clears the current vote if it has been open longer than `timeout`.
+    /// Returns true if a vote was cleared.
+    pub fn expire_vote(&mut self, timeout: Duration) -> bool {
+        let expired = self.vote.as_ref().map_or(false, |v| v.started.elapsed() > timeout);
+        if expired {
+            self.vote = None;
+        }
+        return expired;
+    }
+
     /// Returns a String iterator that iterates through the list of words.
     pub fn words_iter(&self) -> Keys<String, Guesser> {
         return self.words.keys();
@@ -64,6 +167,8 @@ impl<T> CoopGame<T> {
             if p.name == name {
                 if p.did_quit() {
                     p.send = Some(send);
+                    p.last_pong = Instant::now();
+                    p.gen = p.gen.wrapping_add(1);
                     return JoinResult::Ok(n);
                 } else {
                     return JoinResult::Taken(send);
@@ -105,6 +210,10 @@ impl<T> CoopGame<T> {
         self.players[player_num].send = None;
         self.players[player_num].gaveup = false;
 
+        if self.vote.as_ref().map_or(false, |v| v.initiator == player_num) {
+            self.vote = None;
+        }
+
         let mut result = QuitResult::AllQuit;
         for p in self.players.iter() {
             if !p.did_quit() {
@@ -0,0 +1,41 @@
+extern crate thiserror;
+
+use std::string::FromUtf8Error;
+use thiserror::Error;
+use websocket::result::WebSocketError;
+
+use protocol::ParseError;
+
+/// Anything that can go wrong while handling a connection.  Every variant
+/// here is something a client can plausibly trigger (malformed messages, a
+/// dead socket, a stale game name) and so is handled by closing the
+/// connection gracefully instead of panicking the whole server thread.
+#[derive(Error, Debug)]
+pub enum SlError {
+    #[error("malformed message")]
+    Protocol,
+
+    #[error("websocket error: {0}")]
+    WebSocket(#[from] WebSocketError),
+
+    #[error("message was not valid utf-8")]
+    Utf8,
+
+    #[error("no such game")]
+    GameNotFound,
+
+    #[error("invalid word")]
+    InvalidWord,
+}
+
+impl From<FromUtf8Error> for SlError {
+    fn from(_: FromUtf8Error) -> SlError {
+        SlError::Utf8
+    }
+}
+
+impl From<ParseError> for SlError {
+    fn from(_: ParseError) -> SlError {
+        SlError::Protocol
+    }
+}
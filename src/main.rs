@@ -11,20 +11,42 @@ use std::sync::{Arc, Mutex, Condvar, RwLock};
 use std::thread;
 use std::time::Duration;
 use websocket::Server;
+use websocket::Sender;
+use websocket::dataframe::{DataFrame, Opcode};
 use websocket::server::request::RequestUri;
 
+mod clientcomp;
 mod clientcoop;
+mod comp;
 mod coop;
+mod error;
+mod protocol;
+
+use error::SlError;
+use protocol::ServerMessage;
 
 /// How long we should wait before destroying an empty game
 const GAME_KILL_TIMER_MILLIS: u64 = 86400000;
 
 #[derive(Debug)]
 enum URLAction {
-    Host(String),
+    /// Hosting a game, supplying the host's player name and whether the game
+    /// should show up in `/ws/list`.
+    Host(String, bool),
     Join(String),
 }
 
+/// What a parsed URL asked the server to do.
+#[derive(Debug)]
+enum Route {
+    /// Host or join a game.  The bool is true for coop, false for
+    /// competitive.
+    Game(bool, URLAction),
+
+    /// List the public coop games currently open.
+    ListCoop,
+}
+
 struct GameEntry<T> {
     game: Mutex<T>,
 
@@ -37,37 +59,47 @@ struct GameEntry<T> {
     /// not yet chosen their name.  When this drops to zero, the deletion timer
     /// for this game will begin.
     connections: Mutex<u8>,
+
+    /// Whether this game should be reported by `/ws/list`.
+    public: bool,
 }
 
-/// Parses an action from a URL.  If this is a coop game, the bool value will
-/// be true.
-fn get_urlaction(url: &RequestUri) -> Option<(bool, URLAction)> {
+/// Parses a route out of a URL.
+fn get_urlaction(url: &RequestUri) -> Option<Route> {
     lazy_static! {
-        static ref RE: Regex = Regex::new(r"(?x)^/ws
+        static ref RE: Regex = Regex::new(r"(?x)^/ws(?:
                   # Possibility 1: hosting a game, and supplying
-                  # player name
-                  /(?:(hostcoop|hostcomp)/([a-zA-Z0-9]{1,10})
+                  # player name.  hostcoop_private hosts a coop game that is
+                  # left out of the /ws/list browser.
+                  /(?:(hostcoop_private|hostcoop|hostcomp)/([a-zA-Z0-9]{1,10})
 
                   # Possibility 2: joining a pre-existing game.
                   # If the game name starts with a c, this is a coop
                   # game; otherwise it is a competitive game.
-                     |join/(c|m)([a-z0-9]{5,8}))$").unwrap();
+                     |join/(c|m)([a-z0-9]{5,8}))
+                  )$").unwrap();
     }
 
     if let &RequestUri::AbsolutePath(ref path) = url {
+        if path == "/ws/list" {
+            return Some(Route::ListCoop);
+        }
         if let Some(cap) = RE.captures(path) {
             if cap.at(1) == Some("hostcoop") {
                 return cap.at(2).map(|x|
-                                     (true, URLAction::Host(String::from(x))));
+                     Route::Game(true, URLAction::Host(String::from(x), true)));
+            } else if cap.at(1) == Some("hostcoop_private") {
+                return cap.at(2).map(|x|
+                     Route::Game(true, URLAction::Host(String::from(x), false)));
             } else if cap.at(1) == Some("hostcomp") {
                 return cap.at(2).map(|x|
-                                     (false, URLAction::Host(String::from(x))));
+                     Route::Game(false, URLAction::Host(String::from(x), false)));
             } else if cap.at(3) == Some("c") {
                 return cap.at(4).map(|x|
-                                     (true, URLAction::Join(String::from(x))));
+                     Route::Game(true, URLAction::Join(String::from(x))));
             } else if cap.at(3) == Some("m") {
                 return cap.at(4).map(|x|
-                                     (false, URLAction::Join(String::from(x))));
+                     Route::Game(false, URLAction::Join(String::from(x))));
             }
         }
     }
@@ -87,110 +119,321 @@ fn generate_gamename() -> String {
     return s;
 }
 
+/// Waits for a game to become idle (no connections), then either destroys it
+/// or keeps it alive if someone rejoined in the meantime.
+fn reap_if_idle<T>(games: &RwLock<HashMap<String, Arc<GameEntry<T>>>>,
+                    game_entry: &GameEntry<T>,
+                    gamename: String) {
+    let condvar = &game_entry.idle_condvar;
+    let connections = &game_entry.connections;
+
+    let mut connections = connections.lock().unwrap();
+    *connections -= 1;
+
+    // If there is no one connected, start a timer to destroy the game
+    if *connections == 0 {
+        let dur = Duration::from_millis(GAME_KILL_TIMER_MILLIS);
+        let r = condvar.wait_timeout(connections, dur);
+        let (_, r) = r.unwrap();
+        if r.timed_out() {
+            let mut games = games.write().unwrap();
+            println!("destroying game {}", gamename);
+            games.remove(&gamename);
+        } else {
+            println!("saved {}", gamename);
+        }
+    }
+}
+
+/// Sends a `:game <name> <host> <players> <words>` line for every public
+/// coop game currently open.
+fn list_coop_games(send: &mut clientcoop::WSSend,
+                    coop_games: &RwLock<HashMap<String, Arc<GameEntry<clientcoop::WSGame>>>>) {
+    let games = coop_games.read().unwrap();
+
+    for (gamename, entry) in games.iter() {
+        if !entry.public {
+            continue;
+        }
+
+        let (host, words) = {
+            let game = entry.game.lock().unwrap();
+            (game.players[0].name.clone(), game.words.len() as u32)
+        };
+        let players = *entry.connections.lock().unwrap() as u32;
+
+        let msg = ServerMessage::GameInfo(gamename.clone(), host, players, words);
+        let frame = DataFrame::new(true, Opcode::Text, msg.to_wire().into_bytes());
+        send.send_dataframe(&frame);
+    }
+}
+
+/// Sends a `:error <reason>` frame to a client whose connection is about to
+/// be closed because of an `SlError`.
+fn send_error(send: &mut clientcoop::WSSend, err: &SlError) {
+    let msg = ServerMessage::Error(err.to_string());
+    let frame = DataFrame::new(true, Opcode::Text, msg.to_wire().into_bytes());
+    send.send_dataframe(&frame);
+}
+
+/// Hosts or joins a coop game and runs it to completion, reporting an
+/// `SlError` from hosting, from looking up the game, or from `game_loop` (if
+/// the player is still connected) to the client with a `:error` frame instead
+/// of panicking the connection thread.  An error from `join_coop` is only
+/// logged, since `join_coop` has taken the sender.
+fn handle_coop(send: clientcoop::WSSend,
+                mut receive: clientcoop::WSReceive,
+                action: URLAction,
+                coop_games: &RwLock<HashMap<String, Arc<GameEntry<clientcoop::WSGame>>>>) {
+    let (game_entry, pnum, gamename) = match action {
+        URLAction::Host(name, public) => {
+            let game = match clientcoop::host_coop(send, &mut receive, name) {
+                Ok(game) => game,
+                Err((mut send, e)) => {
+                    println!("host_coop failed: {}", e);
+                    send_error(&mut send, &e);
+                    return;
+                },
+            };
+
+            let game_entry = Arc::new(GameEntry {
+                game: Mutex::new(game),
+                idle_condvar: Condvar::new(),
+                connections: Mutex::new(1),
+                public: public,
+            });
+
+            // Keep generating a gamename until we find one
+            // that hasn't been taken, then place the game into
+            // the dict
+            let mut gamename = generate_gamename();
+            {
+                let game_entry = game_entry.clone();
+                let mut coop_games = coop_games.write().unwrap();
+                loop {
+                    match coop_games.entry(gamename) {
+                        // if this gamename has already been
+                        // taken, generate a new one
+                        Entry::Occupied(_) =>
+                            gamename = generate_gamename(),
+                            Entry::Vacant(e) => {
+                                gamename = e.key().to_owned();
+                                e.insert(game_entry);
+                                break;
+                            },
+                    }
+                }
+            };
+
+            clientcoop::send_gamename(gamename.clone(), &game_entry.game);
+            (game_entry, 0, gamename)
+        },
+        URLAction::Join(gamename) => {
+            let game_entry = {
+                let coop_games = coop_games.read().unwrap();
+                match coop_games.get(&gamename) {
+                    Some(g) => g.clone(),
+                    None => {
+                        let mut send = send;
+                        send_error(&mut send, &SlError::GameNotFound);
+                        return;
+                    },
+                }
+            };
+
+            game_entry.idle_condvar.notify_all();
+            *game_entry.connections.lock().unwrap() += 1;
+
+            let pnum = match clientcoop::join_coop(send, &mut receive, &game_entry.game) {
+                Ok(pnum) => pnum,
+                Err(e) => {
+                    println!("join_coop failed: {}", e);
+                    reap_if_idle(coop_games, &game_entry, gamename);
+                    return;
+                },
+            };
+            (game_entry, pnum, gamename)
+        },
+    };
+
+    {
+        let gen = game_entry.game.lock().unwrap().players[pnum].gen;
+        let game_entry = game_entry.clone();
+        thread::spawn(move || {
+            clientcoop::heartbeat(pnum, gen, &game_entry.game);
+        });
+    }
+
+    let g = &game_entry.game;
+
+    if let Err(e) = clientcoop::game_loop(&mut receive, pnum, g) {
+        println!("player quit: {}", e);
+        if let Some(send) = g.lock().unwrap().players[pnum].send.as_mut() {
+            send_error(send, &e);
+        }
+    }
+
+    clientcoop::on_disconnect(pnum, g);
+
+    reap_if_idle(coop_games, &game_entry, gamename);
+}
+
+/// Hosts or joins a competitive game and runs it to completion, reporting an
+/// `SlError` from hosting, from looking up the game, or from `game_loop` (if
+/// the player is still connected) to the client with a `:error` frame instead
+/// of panicking the connection thread.  An error from `join_comp` is only
+/// logged, since `join_comp` has taken the sender.
+fn handle_comp(send: clientcomp::WSSend,
+                mut receive: clientcomp::WSReceive,
+                action: URLAction,
+                comp_games: &RwLock<HashMap<String, Arc<GameEntry<clientcomp::WSGame>>>>) {
+    let (game_entry, pnum, gamename) = match action {
+        URLAction::Host(name, _public) => {
+            let game = match clientcomp::host_comp(send, &mut receive, name) {
+                Ok(game) => game,
+                Err((mut send, e)) => {
+                    println!("host_comp failed: {}", e);
+                    send_error(&mut send, &e);
+                    return;
+                },
+            };
+
+            let game_entry = Arc::new(GameEntry {
+                game: Mutex::new(game),
+                idle_condvar: Condvar::new(),
+                connections: Mutex::new(1),
+                // Competitive games aren't browsable yet.
+                public: false,
+            });
+
+            // Keep generating a gamename until we find one
+            // that hasn't been taken, then place the game into
+            // the dict
+            let mut gamename = generate_gamename();
+            {
+                let game_entry = game_entry.clone();
+                let mut comp_games = comp_games.write().unwrap();
+                loop {
+                    match comp_games.entry(gamename) {
+                        // if this gamename has already been
+                        // taken, generate a new one
+                        Entry::Occupied(_) =>
+                            gamename = generate_gamename(),
+                            Entry::Vacant(e) => {
+                                gamename = e.key().to_owned();
+                                e.insert(game_entry);
+                                break;
+                            },
+                    }
+                }
+            };
+
+            clientcomp::send_gamename(gamename.clone(), &game_entry.game);
+            (game_entry, 0, gamename)
+        },
+        URLAction::Join(gamename) => {
+            let game_entry = {
+                let comp_games = comp_games.read().unwrap();
+                match comp_games.get(&gamename) {
+                    Some(g) => g.clone(),
+                    None => {
+                        let mut send = send;
+                        send_error(&mut send, &SlError::GameNotFound);
+                        return;
+                    },
+                }
+            };
+
+            game_entry.idle_condvar.notify_all();
+            *game_entry.connections.lock().unwrap() += 1;
+
+            let pnum = match clientcomp::join_comp(send, &mut receive, &game_entry.game) {
+                Ok(pnum) => pnum,
+                Err(e) => {
+                    println!("join_comp failed: {}", e);
+                    reap_if_idle(comp_games, &game_entry, gamename);
+                    return;
+                },
+            };
+            (game_entry, pnum, gamename)
+        },
+    };
+
+    {
+        let gen = game_entry.game.lock().unwrap().players[pnum].gen;
+        let game_entry = game_entry.clone();
+        thread::spawn(move || {
+            clientcomp::heartbeat(pnum, gen, &game_entry.game);
+        });
+    }
+
+    let g = &game_entry.game;
+
+    if let Err(e) = clientcomp::game_loop(&mut receive, pnum, g) {
+        println!("player quit: {}", e);
+        if let Some(send) = g.lock().unwrap().players[pnum].send.as_mut() {
+            send_error(send, &e);
+        }
+    }
+
+    clientcomp::on_disconnect(pnum, g);
+
+    reap_if_idle(comp_games, &game_entry, gamename);
+}
+
 fn main() {
     let coop_games = Arc::new(RwLock::new(HashMap::new()));
+    let comp_games = Arc::new(RwLock::new(HashMap::new()));
 
     let server = Server::bind("127.0.0.1:8754").unwrap();
 
     for connection in server {
         let coop_games = coop_games.clone();
+        let comp_games = comp_games.clone();
 
         thread::spawn(move || {
-            let request = connection.unwrap().read_request().unwrap();
-
-            if let Some((is_coop, action)) = get_urlaction(&request.url) {
-                request.validate().unwrap();
-                let response = request.accept();
-                let client = response.send().unwrap();
-                let (send, mut receive) = client.split();
-
-                if is_coop {
-                    let (game_entry, pnum, gamename) = match action {
-                        URLAction::Host(name) => {
-                            let game = clientcoop::host_coop(send,
-                                                             &mut receive,
-                                                             name);
-
-                            let game_entry = Arc::new(GameEntry {
-                                game: Mutex::new(game),
-                                idle_condvar: Condvar::new(),
-                                connections: Mutex::new(1),
-                            });
-
-                            // Keep generating a gamename until we find one 
-                            // that hasn't been taken, then place the game into
-                            // the dict
-                            let mut gamename = generate_gamename();
-                            {
-                                let game_entry = game_entry.clone();
-                                let mut coop_games =
-                                        coop_games.write().unwrap();
-                                loop {
-                                    match coop_games.entry(gamename) {
-                                        // if this gamename has already been
-                                        // taken, generate a new one
-                                        Entry::Occupied(_) =>
-                                            gamename = generate_gamename(),
-                                            Entry::Vacant(e) => {
-                                                gamename = e.key().to_owned();
-                                                e.insert(game_entry);
-                                                break;
-                                            },
-                                    }
-                                }
-                            };
-
-                            clientcoop::send_gamename(gamename.clone(), &game_entry.game);
-                            (game_entry, 0, gamename)
-                        },
-                        URLAction::Join(gamename) => {
-                            let game_entry = {
-                                let coop_games = coop_games.read().unwrap();
-                                coop_games.get(&gamename).unwrap().clone()
-                            };
-
-                            game_entry.idle_condvar.notify_all();
-                            *game_entry.connections.lock().unwrap() += 1;
-
-                            let pnum = clientcoop::join_coop(send,
-                                                             &mut receive,
-                                                             &game_entry.game)
-                                                            .unwrap();
-                            (game_entry, pnum, gamename)
-                        }
-                    };
-
-                    let mut g = &game_entry.game;
-
-                    let err = clientcoop::game_loop(&mut receive, pnum, &g);
-
-                    println!("player quit {:?}", err);
-
-                    clientcoop::on_disconnect(pnum, &mut g);
-
-                    let condvar = &game_entry.idle_condvar;
-                    let connections = &game_entry.connections;
-
-                    let mut connections = connections.lock().unwrap();
-                    *connections -= 1;
-
-                    // If there is no one connected, start a timer to destroy
-                    // the game
-                    if *connections == 0 {
-                        let dur = Duration::from_millis(GAME_KILL_TIMER_MILLIS);
-                        let r = condvar.wait_timeout(connections, dur);
-                        let (_, r) = r.unwrap();
-                        if r.timed_out() {
-                            let mut coop_games = coop_games.write().unwrap();
-                            println!("destroying game {}", gamename);
-                            coop_games.remove(&gamename);
-                        } else {
-                            println!("saved {}", gamename);
-                        }
-                    }
-                }
+            let request = match connection.unwrap().read_request() {
+                Ok(request) => request,
+                Err(e) => {
+                    println!("failed to read request: {:?}", e);
+                    return;
+                },
+            };
+
+            let route = match get_urlaction(&request.url) {
+                Some(route) => route,
+                None => return,
+            };
+
+            if let Err(e) = request.validate() {
+                println!("invalid request: {:?}", e);
+                return;
+            }
+            let response = request.accept();
+            let client = match response.send() {
+                Ok(client) => client,
+                Err(e) => {
+                    println!("handshake failed: {:?}", e);
+                    return;
+                },
+            };
+
+            let (is_coop, action) = match route {
+                Route::ListCoop => {
+                    let (mut send, _receive) = client.split();
+                    list_coop_games(&mut send, &coop_games);
+                    return;
+                },
+                Route::Game(is_coop, action) => (is_coop, action),
+            };
+
+            let (send, receive) = client.split();
+
+            if is_coop {
+                handle_coop(send, receive, action, &coop_games);
+            } else {
+                handle_comp(send, receive, action, &comp_games);
             }
-            return;
         });
     }
 }
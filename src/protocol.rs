@@ -0,0 +1,282 @@
+extern crate nom;
+
+use self::nom::IResult;
+use std::fmt;
+use std::str;
+
+/// A message sent by a client, once parsed out of the raw websocket frame.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ClientMessage {
+    Attempt(String),
+    GiveUp,
+    UnGiveUp,
+    Chat(String),
+    SetName(String),
+    WordList(Vec<(String, bool)>),
+    CallVoteKick(String),
+    CallVoteReveal(String),
+    Vote(bool),
+}
+
+/// A message to be sent to a client.  `to_wire` renders it into the
+/// colon-command text format used on the wire; `parse` does not read these
+/// messages back.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ServerMessage {
+    Ok,
+    BadName,
+    Taken,
+    Join(String),
+    Quit(String),
+    GiveUp(String),
+    UnGiveUp(String),
+    AllGiveUp,
+    Chat(String, String),
+    Attempt(String, String),
+    AttemptGaveUp(String),
+    Score(String, u32),
+    Winner(String),
+    Error(String),
+    VoteCount(u32, u32),
+    Kicked(String),
+
+    /// A single row of the `/ws/list` game browser: name, host, connected
+    /// players, and word count.
+    GameInfo(String, String, u32, u32),
+}
+
+impl ServerMessage {
+    pub fn to_wire(&self) -> String {
+        match *self {
+            ServerMessage::Ok => String::from(":ok"),
+            ServerMessage::BadName => String::from(":badname"),
+            ServerMessage::Taken => String::from(":taken"),
+            ServerMessage::Join(ref name) => format!(":join {}", name),
+            ServerMessage::Quit(ref name) => format!(":quit {}", name),
+            ServerMessage::GiveUp(ref name) => format!(":giveup {}", name),
+            ServerMessage::UnGiveUp(ref name) => format!(":ungiveup {}", name),
+            ServerMessage::AllGiveUp => String::from(":allgiveup"),
+            ServerMessage::Chat(ref name, ref msg) => format!(":chat {} {}", name, msg),
+            ServerMessage::Attempt(ref word, ref name) => format!(":attempt {} {}", word, name),
+            ServerMessage::AttemptGaveUp(ref word) => format!(":attempt {} _", word),
+            ServerMessage::Score(ref name, points) => format!(":score {} {}", name, points),
+            ServerMessage::Winner(ref name) => format!(":winner {}", name),
+            ServerMessage::Error(ref reason) => format!(":error {}", reason),
+            ServerMessage::VoteCount(yes, total) => format!(":votecount {} {}", yes, total),
+            ServerMessage::Kicked(ref name) => format!(":kicked {}", name),
+            ServerMessage::GameInfo(ref name, ref host, players, words) =>
+                format!(":game {} {} {} {}", name, host, players, words),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// No command matched the message: either the `:command` is not
+    /// recognized, or its arguments were malformed (e.g. a word that is not
+    /// 3 to 6 lowercase letters, or a name with bad characters).
+    InvalidArgument,
+
+    /// `parse` matched a command, but text was left over after it (e.g.
+    /// `:giveup extra`).
+    Malformed,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::InvalidArgument => write!(f, "invalid argument"),
+            ParseError::Malformed => write!(f, "malformed message"),
+        }
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_lowercase()
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric()
+}
+
+named!(word_token<&str, &str>, take_while1_s!(is_word_char));
+
+named!(name_token<&str, &str>,
+    verify!(take_while1_s!(is_name_char), |s: &str| s.len() >= 1 && s.len() <= 10));
+
+named!(attempt_cmd<&str, ClientMessage>,
+    do_parse!(
+        tag_s!(":attempt ") >>
+        word: word_token >>
+        verify!(value!(word.len()), |len: usize| len >= 3 && len <= 6) >>
+        (ClientMessage::Attempt(word.to_owned()))
+    )
+);
+
+named!(giveup_cmd<&str, ClientMessage>,
+    map!(tag_s!(":giveup"), |_| ClientMessage::GiveUp));
+
+named!(ungiveup_cmd<&str, ClientMessage>,
+    map!(tag_s!(":ungiveup"), |_| ClientMessage::UnGiveUp));
+
+named!(chat_cmd<&str, ClientMessage>,
+    do_parse!(
+        tag_s!(":chat ") >>
+        msg: rest_s >>
+        (ClientMessage::Chat(msg.to_owned()))
+    )
+);
+
+named!(setname_cmd<&str, ClientMessage>,
+    map!(name_token, |n: &str| ClientMessage::SetName(n.to_owned())));
+
+named!(callvote_kick_cmd<&str, ClientMessage>,
+    do_parse!(
+        tag_s!(":callvote kick ") >>
+        name: name_token >>
+        (ClientMessage::CallVoteKick(name.to_owned()))
+    )
+);
+
+named!(callvote_reveal_cmd<&str, ClientMessage>,
+    do_parse!(
+        tag_s!(":callvote reveal ") >>
+        word: word_token >>
+        (ClientMessage::CallVoteReveal(word.to_owned()))
+    )
+);
+
+named!(vote_cmd<&str, ClientMessage>,
+    alt!(
+        map!(tag_s!(":vote yes"), |_| ClientMessage::Vote(true)) |
+        map!(tag_s!(":vote no"), |_| ClientMessage::Vote(false))
+    )
+);
+
+fn parse_one_word(token: &str) -> Option<(String, bool)> {
+    let (word, already_guessed) = if token.ends_with('_') {
+        (&token[..token.len() - 1], true)
+    } else {
+        (token, false)
+    };
+    if word.len() >= 3 && word.len() <= 6 && word.chars().all(is_word_char) {
+        Some((word.to_owned(), already_guessed))
+    } else {
+        None
+    }
+}
+
+named!(wordlist_cmd<&str, ClientMessage>,
+    map_opt!(rest_s, |s: &str| {
+        let mut words = Vec::new();
+        for token in s.split(' ').take(75) {
+            match parse_one_word(token) {
+                Some(w) => words.push(w),
+                None => return None,
+            }
+        }
+        Some(ClientMessage::WordList(words))
+    })
+);
+
+/// Parses a single in-game client message, as read by `game_loop`.  Only
+/// the in-game commands are tried: this never returns `WordList` or
+/// `SetName`; the host's word list and a joining player's name are parsed by
+/// `parse_wordlist` and `parse_name`.
+pub fn parse(input: &str) -> Result<ClientMessage, ParseError> {
+    match alt!(input, attempt_cmd | giveup_cmd | ungiveup_cmd | chat_cmd
+                      | callvote_kick_cmd | callvote_reveal_cmd | vote_cmd) {
+        IResult::Done(rest, msg) => {
+            if rest.is_empty() {
+                Ok(msg)
+            } else {
+                Err(ParseError::Malformed)
+            }
+        },
+        _ => Err(ParseError::InvalidArgument),
+    }
+}
+
+/// Parses a message that is expected to be a player name, as sent while
+/// joining a game.
+pub fn parse_name(input: &str) -> Result<ClientMessage, ParseError> {
+    match setname_cmd(input) {
+        IResult::Done(rest, msg) => {
+            if rest.is_empty() {
+                Ok(msg)
+            } else {
+                Err(ParseError::InvalidArgument)
+            }
+        },
+        _ => Err(ParseError::InvalidArgument),
+    }
+}
+
+/// Parses a message that is expected to be the host's initial word list.
+pub fn parse_wordlist(input: &str) -> Result<ClientMessage, ParseError> {
+    match wordlist_cmd(input) {
+        IResult::Done(_, msg) => Ok(msg),
+        _ => Err(ParseError::InvalidArgument),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attempt_accepts_word_in_length_range() {
+        assert_eq!(parse(":attempt cat"), Ok(ClientMessage::Attempt(String::from("cat"))));
+        assert_eq!(parse(":attempt elephant"), Err(ParseError::InvalidArgument));
+    }
+
+    #[test]
+    fn attempt_rejects_uppercase_or_digits() {
+        assert_eq!(parse(":attempt CAT"), Err(ParseError::InvalidArgument));
+        assert_eq!(parse(":attempt ca7"), Err(ParseError::InvalidArgument));
+    }
+
+    #[test]
+    fn parse_rejects_trailing_garbage() {
+        assert_eq!(parse(":giveup extra"), Err(ParseError::Malformed));
+    }
+
+    #[test]
+    fn vote_commands_parse() {
+        assert_eq!(parse(":callvote kick bob"), Ok(ClientMessage::CallVoteKick(String::from("bob"))));
+        assert_eq!(parse(":callvote reveal cat"), Ok(ClientMessage::CallVoteReveal(String::from("cat"))));
+        assert_eq!(parse(":vote yes"), Ok(ClientMessage::Vote(true)));
+        assert_eq!(parse(":vote no"), Ok(ClientMessage::Vote(false)));
+    }
+
+    #[test]
+    fn name_accepts_ascii_alphanumeric() {
+        assert_eq!(parse_name("Bob9"), Ok(ClientMessage::SetName(String::from("Bob9"))));
+    }
+
+    #[test]
+    fn name_rejects_non_ascii() {
+        // is_name_char must stay ASCII-only; nom's builtin `alphanumeric` is
+        // Unicode-aware and would wrongly accept this.
+        assert_eq!(parse_name("h\u{e9}llo"), Err(ParseError::InvalidArgument));
+    }
+
+    #[test]
+    fn name_rejects_bad_length() {
+        assert_eq!(parse_name(""), Err(ParseError::InvalidArgument));
+        assert_eq!(parse_name("abcdefghijk"), Err(ParseError::InvalidArgument));
+    }
+
+    #[test]
+    fn wordlist_parses_already_guessed_marker() {
+        assert_eq!(parse_wordlist("cat dog_"), Ok(ClientMessage::WordList(vec![
+            (String::from("cat"), false),
+            (String::from("dog"), true),
+        ])));
+    }
+
+    #[test]
+    fn wordlist_rejects_word_out_of_range() {
+        assert_eq!(parse_wordlist("cat ab"), Err(ParseError::InvalidArgument));
+    }
+}